@@ -23,7 +23,9 @@ pub struct CommonArgs {
     #[arg(long, env = "WMD_OUT_DIR")]
     pub out_dir: PathBuf,
 
-    /// HTTP cache mode to use when making requests.
+    /// HTTP cache mode to use when making metadata requests (`dumpstatus.json` and friends).
+    ///
+    /// Job file downloads never use this cache: see `http::download_client`.
     ///
     /// See the `http-cache` crate documentation for an explanation of each of the options:
     /// https://docs.rs/http-cache/0.10.1/http_cache/enum.CacheMode.html
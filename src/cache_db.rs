@@ -0,0 +1,213 @@
+use crate::Result;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// A key identifying a single job file in the cache database.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CacheKey {
+    pub dump: String,
+    pub version: String,
+    pub job: String,
+    pub file: String,
+}
+
+impl CacheKey {
+    pub fn path(&self, out_dir: &Path) -> PathBuf {
+        out_dir.join(&self.dump).join(&self.version).join(&self.job).join(&self.file)
+    }
+}
+
+/// A row read back from the cache database.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub key: CacheKey,
+    pub last_use_unix_secs: i64,
+    pub size_bytes: u64,
+}
+
+/// The SQLite database tracking last-use times for downloaded job files, modeled on cargo's
+/// global cache GC.
+///
+/// Updates are buffered in memory via `record_access` and only written to disk by `flush`, in a
+/// single transaction, so a download run with many small files doesn't pay one SQLite write per
+/// file.
+pub struct CacheDb {
+    conn: rusqlite::Connection,
+    pending: Mutex<HashMap<CacheKey, (i64, u64)>>,
+}
+
+impl CacheDb {
+    pub fn db_path(out_dir: &Path) -> PathBuf {
+        out_dir.join("_cache.sqlite3")
+    }
+
+    pub fn open(out_dir: &Path) -> Result<CacheDb> {
+        std::fs::create_dir_all(out_dir)?;
+        let conn = rusqlite::Connection::open(Self::db_path(out_dir))?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS last_use (
+                dump TEXT NOT NULL,
+                version TEXT NOT NULL,
+                job TEXT NOT NULL,
+                file TEXT NOT NULL,
+                size_bytes INTEGER NOT NULL,
+                last_use_unix_secs INTEGER NOT NULL,
+                PRIMARY KEY (dump, version, job, file)
+            );")?;
+        Ok(CacheDb { conn, pending: Mutex::new(HashMap::new()) })
+    }
+
+    /// Buffer a last-use update for `key`; not written to disk until `flush`.
+    pub fn record_access(&self, key: CacheKey, unix_secs: i64, size_bytes: u64) {
+        self.pending.lock().expect("cache db pending lock poisoned")
+            .insert(key, (unix_secs, size_bytes));
+    }
+
+    /// Write all buffered accesses to disk in a single transaction.
+    pub fn flush(&mut self) -> Result<()> {
+        let pending = std::mem::take(&mut *self.pending.lock().expect("cache db pending lock poisoned"));
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let count = pending.len();
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO last_use (dump, version, job, file, size_bytes, last_use_unix_secs)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT (dump, version, job, file)
+                 DO UPDATE SET size_bytes = excluded.size_bytes,
+                               last_use_unix_secs = excluded.last_use_unix_secs")?;
+            for (key, (unix_secs, size_bytes)) in &pending {
+                stmt.execute(rusqlite::params![key.dump, key.version, key.job, key.file,
+                                                size_bytes, unix_secs])?;
+            }
+        }
+        tx.commit()?;
+
+        tracing::debug!(count, "flushed last-use tracker to cache database");
+        Ok(())
+    }
+
+    /// Entries whose last use is strictly older than `cutoff_unix_secs`.
+    pub fn entries_older_than(&self, cutoff_unix_secs: i64) -> Result<Vec<CacheEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT dump, version, job, file, size_bytes, last_use_unix_secs
+             FROM last_use WHERE last_use_unix_secs < ?1")?;
+        let entries = stmt.query_map(rusqlite::params![cutoff_unix_secs], row_to_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    /// All entries, ordered least-recently-used first.
+    pub fn all_entries_by_last_use(&self) -> Result<Vec<CacheEntry>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT dump, version, job, file, size_bytes, last_use_unix_secs
+             FROM last_use ORDER BY last_use_unix_secs ASC")?;
+        let entries = stmt.query_map([], row_to_entry)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(entries)
+    }
+
+    pub fn remove_entry(&mut self, key: &CacheKey) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM last_use WHERE dump = ?1 AND version = ?2 AND job = ?3 AND file = ?4",
+            rusqlite::params![key.dump, key.version, key.job, key.file])?;
+        Ok(())
+    }
+}
+
+fn row_to_entry(row: &rusqlite::Row) -> rusqlite::Result<CacheEntry> {
+    Ok(CacheEntry {
+        key: CacheKey {
+            dump: row.get(0)?,
+            version: row.get(1)?,
+            job: row.get(2)?,
+            file: row.get(3)?,
+        },
+        size_bytes: row.get(4)?,
+        last_use_unix_secs: row.get(5)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// A fresh, uniquely-named out_dir under the system temp dir, removed when dropped.
+    struct TestOutDir(PathBuf);
+
+    impl TestOutDir {
+        fn new(label: &str) -> TestOutDir {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+            let path = std::env::temp_dir()
+                .join(format!("wmd-cache-db-test-{label}-{}-{nanos}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            TestOutDir(path)
+        }
+    }
+
+    impl Drop for TestOutDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    fn key(file: &str) -> CacheKey {
+        CacheKey {
+            dump: "enwiki".to_string(), version: "20230301".to_string(),
+            job: "metacurrentdumprecombine".to_string(), file: file.to_string(),
+        }
+    }
+
+    #[test]
+    fn entries_older_than_excludes_entries_at_or_after_the_cutoff() {
+        let out_dir = TestOutDir::new("entries-older-than");
+        let mut db = CacheDb::open(&out_dir.0).unwrap();
+        db.record_access(key("old.xml.bz2"), 100, 10);
+        db.record_access(key("at-cutoff.xml.bz2"), 150, 10);
+        db.record_access(key("new.xml.bz2"), 200, 10);
+        db.flush().unwrap();
+
+        let older = db.entries_older_than(150).unwrap();
+
+        assert_eq!(older.iter().map(|e| e.key.file.as_str()).collect::<Vec<_>>(),
+                   vec!["old.xml.bz2"]);
+    }
+
+    #[test]
+    fn all_entries_by_last_use_orders_oldest_first() {
+        let out_dir = TestOutDir::new("all-entries-by-last-use");
+        let mut db = CacheDb::open(&out_dir.0).unwrap();
+        db.record_access(key("b.xml.bz2"), 200, 10);
+        db.record_access(key("a.xml.bz2"), 100, 10);
+        db.record_access(key("c.xml.bz2"), 300, 10);
+        db.flush().unwrap();
+
+        let entries = db.all_entries_by_last_use().unwrap();
+
+        assert_eq!(entries.iter().map(|e| e.key.file.as_str()).collect::<Vec<_>>(),
+                   vec!["a.xml.bz2", "b.xml.bz2", "c.xml.bz2"]);
+    }
+
+    #[test]
+    fn record_access_then_flush_overwrites_an_existing_entry() {
+        let out_dir = TestOutDir::new("overwrite-entry");
+        let mut db = CacheDb::open(&out_dir.0).unwrap();
+        db.record_access(key("a.xml.bz2"), 100, 10);
+        db.flush().unwrap();
+        db.record_access(key("a.xml.bz2"), 200, 20);
+        db.flush().unwrap();
+
+        let entries = db.all_entries_by_last_use().unwrap();
+
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].last_use_unix_secs, 200);
+        assert_eq!(entries[0].size_bytes, 20);
+    }
+}
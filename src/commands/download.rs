@@ -1,12 +1,18 @@
-use anyhow::Context;
+use anyhow::{bail, Context};
 use crate::{
     args::{CommonArgs, DumpNameArg, FileNameRegexArg, JobNameArg, VersionSpecArg},
+    cache_db::{CacheDb, CacheKey},
     http,
-    operations::{self, DownloadJobFileResultKind},
+    lock_file::LockFile,
+    operations::{self, DownloadJobFileResultKind, VerifyMode},
     Result,
     TempDir,
 };
-use std::time::Instant;
+use futures::stream::{self, StreamExt};
+use std::{
+    collections::BTreeMap,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 
 /// Download latest dump job files
 #[derive(clap::Args, Clone, Debug)]
@@ -32,25 +38,67 @@ pub struct Args {
 
     /// Specify the URL of a mirror to download job files from. Only supports http: and https: URLs.
     ///
+    /// May be given more than once, or as a comma-separated list, to provide several mirrors.
+    /// Mirrors are tried in the order given, falling through to the next on a connection error,
+    /// a 404/403 response, or a checksum failure; a file only fails once every mirror has been
+    /// exhausted.
+    ///
     /// If not present tries to read the environment variable `WMD_MIRROR_URL`.
     ///
     /// Examples:
     ///   * https://dumps.wikimedia.org
     ///   * https://ftp.acc.umu.se/mirror/wikimedia.org/dumps
     ///
-    /// Note that only job files are downloaded from this mirror, metadata files are downloaded from https://dumps.wikimedia.org to ensure we get the freshest data.
+    /// Note that only job files are downloaded from these mirrors, metadata files are downloaded from https://dumps.wikimedia.org to ensure we get the freshest data.
     ///
     /// To find a mirror, see https://meta.wikimedia.org/wiki/Mirroring_Wikimedia_project_XML_dumps#Current_mirrors
-    #[arg(long, env = "WMD_MIRROR_URL")]
-    mirror_url: String,
+    #[arg(long, env = "WMD_MIRROR_URL", value_delimiter = ',', required = true)]
+    mirror_url: Vec<String>,
+
+    /// Verify downloaded (and already-present) files against their published digest.
+    ///
+    /// `sha1` and `md5` are verified against the digests published in `dumpstatus.json`.
+    /// Pass `none` to skip verification, e.g. if a dump doesn't publish digests.
+    #[arg(long, value_enum, default_value_t = VerifyMode::Sha1)]
+    verify: VerifyMode,
+
+    /// Maximum number of job files to download concurrently. Must be at least 1.
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(usize).range(1..))]
+    concurrency: usize,
+
+    /// Resume a partially-downloaded `.part` file using an HTTP Range request, instead of
+    /// restarting it from scratch. Enabled by default.
+    #[arg(long, overrides_with = "no_resume", default_value_t = true)]
+    resume: bool,
+
+    /// Disable `--resume`: always restart an interrupted download from scratch.
+    #[arg(long, overrides_with = "resume")]
+    no_resume: bool,
+
+    /// After a file passes checksum verification, also decompress it into a sibling file in
+    /// `out_dir`, e.g. `foo.xml.bz2` -> `foo.xml`. The decoder is chosen from the file's
+    /// extension; `.bz2` and `.gz` are supported. Unpacking is skipped if an up-to-date
+    /// decompressed file already exists.
+    #[arg(long, default_value_t = false)]
+    unpack: bool,
+
+    /// Delete the compressed original after a successful `--unpack`. Has no effect without
+    /// `--unpack`.
+    #[arg(long, default_value_t = false)]
+    remove_compressed: bool,
+}
+
+impl Args {
+    fn resume(&self) -> bool {
+        self.resume && !self.no_resume
+    }
 }
 
 #[tracing::instrument(level = "trace")]
 pub async fn main(args: Args) -> Result<()> {
     let start_time = Instant::now();
 
-    let dump_name = &*args.dump_name.value;
-    let job_name = &*args.job_name.value;
+    let _lock = LockFile::acquire(&args.common.out_dir)?;
 
     let metadata_client = http::metadata_client(&args.common)?;
 
@@ -63,50 +111,151 @@ pub async fn main(args: Args) -> Result<()> {
 
     let temp_dir = TempDir::create(&*args.common.out_dir, args.keep_temp_dir)?;
     let download_client = http::download_client(&args.common)?;
+    let mut cache_db = CacheDb::open(&args.common.out_dir)?;
+
+    let results: Vec<Result<(operations::DownloadJobFileResult, Option<u64>)>> =
+        stream::iter(files.iter())
+            .map(|(file_name, file_meta)| {
+                let download_client = &download_client;
+                let args = &args;
+                let ver = &ver;
+                let temp_dir = &temp_dir;
+                let cache_db = &cache_db;
+                async move {
+                    let res =
+                        operations::download_job_file(download_client, &args.dump_name, ver, &args.job_name,
+                                                      &args.mirror_url, file_meta, &*args.common.out_dir,
+                                                      temp_dir, args.resume(), args.verify).await
+                            .with_context(|| format!(
+                                "while downloading job file \
+                                 dump='{dump_name}' \
+                                 version='{ver}' \
+                                 job='{job_name}' \
+                                 file='{file_rel_url}'",
+                                dump_name = &*args.dump_name.value,
+                                ver = ver.0,
+                                job_name = &*args.job_name.value,
+                                file_rel_url = &*file_meta.url))?;
+
+                    // Don't record a last-use time for a file that just failed checksum
+                    // verification and was removed: it no longer exists on disk, and a last-use
+                    // row for it would count its size towards `gc --max-size`'s tracked total.
+                    if res.kind != DownloadJobFileResultKind::ChecksumMismatch {
+                        cache_db.record_access(
+                            CacheKey {
+                                dump: args.dump_name.value.clone(),
+                                version: ver.0.clone(),
+                                job: args.job_name.value.clone(),
+                                file: file_name.clone(),
+                            },
+                            unix_now()?,
+                            res.len);
+                    }
+
+                    let unpacked_len =
+                        if args.unpack && res.kind != DownloadJobFileResultKind::ChecksumMismatch {
+                            // Decompression is synchronous, CPU- and disk-heavy; run it on a
+                            // blocking thread so unpacking one large file doesn't stall the
+                            // runtime thread polling other concurrent downloads.
+                            let final_path = res.final_path.clone();
+                            let remove_compressed = args.remove_compressed;
+                            tokio::task::spawn_blocking(move || {
+                                crate::unpack::unpack_job_file(&final_path, remove_compressed)
+                            }).await.context("unpack task panicked")?
+                                .with_context(|| format!(
+                                    "while unpacking '{}'", res.final_path.display()))?
+                        } else {
+                            None
+                        };
+
+                    Ok((res, unpacked_len))
+                }
+            })
+            .buffer_unordered(args.concurrency)
+            .collect().await;
+
+    drop(temp_dir);
+
+    cache_db.flush().context("while flushing cache last-use database")?;
 
     let mut download_ok: u64 = 0;
     let mut download_len: u64 = 0;
+    let mut download_transferred_len: u64 = 0;
     let mut existing_ok: u64 = 0;
     let mut existing_len: u64 = 0;
+    let mut checksum_mismatch: u64 = 0;
+    let mut unpack_ok: u64 = 0;
+    let mut unpack_len: u64 = 0;
+    let mut mirror_success: BTreeMap<String, u64> = BTreeMap::new();
+    let mut mirror_failure: BTreeMap<String, u64> = BTreeMap::new();
+    let mut failed: u64 = 0;
+
+    for res in results {
+        let (res, unpacked_len) = match res {
+            Ok(res) => res,
+            Err(e) => {
+                // `results` is already fully resolved by the time we get here (buffer_unordered
+                // has finished every file), so bailing out mid-loop on the first error would
+                // throw away the summary for everything else that already succeeded. Log it and
+                // keep going; report the overall failure after the summary is printed.
+                tracing::error!(error = format!("{e:#}"), "job file failed");
+                failed += 1;
+                continue;
+            },
+        };
+
+        for mirror in &res.failed_mirrors {
+            *mirror_failure.entry(mirror.clone()).or_default() += 1;
+        }
+        if let Some(mirror) = &res.mirror {
+            *mirror_success.entry(mirror.clone()).or_default() += 1;
+        }
 
-    for (_file_name, file_meta) in files.iter() {
-        let res =
-            operations::download_job_file(&download_client, &args.dump_name, &ver, &args.job_name,
-                                          &*args.mirror_url, file_meta, &*args.common.out_dir,
-                                          &temp_dir).await
-                .with_context(|| format!(
-                    "while downloading job file \
-                     dump='{dump_name}' \
-                     version='{ver}' \
-                     job='{job_name}' \
-                     file='{file_rel_url}'",
-                    ver = ver.0,
-                    file_rel_url = &*file_meta.url))?;
         match res.kind {
             DownloadJobFileResultKind::DownloadOk => {
                 download_ok += 1;
                 download_len += res.len;
+                download_transferred_len += res.transferred_len;
             },
             DownloadJobFileResultKind::ExistingOk => {
                 existing_ok += 1;
                 existing_len += res.len;
             },
+            DownloadJobFileResultKind::ChecksumMismatch => {
+                checksum_mismatch += 1;
+            },
         }
-    }
 
-    drop(temp_dir);
+        if let Some(len) = unpacked_len {
+            unpack_ok += 1;
+            unpack_len += len;
+        }
+    }
 
     let duration = start_time.elapsed();
 
     tracing::info!(download_ok,
                    download_len,
                    download_len_str = fmt_bytes(download_len),
+                   download_transferred_len,
+                   download_transferred_len_str = fmt_bytes(download_transferred_len),
                    existing_ok,
                    existing_len,
                    existing_len_str = fmt_bytes(existing_len),
+                   checksum_mismatch,
+                   unpack_ok,
+                   unpack_len,
+                   unpack_len_str = fmt_bytes(unpack_len),
+                   failed,
+                   ?mirror_success,
+                   ?mirror_failure,
                    ?duration,
                    "download command complete");
 
+    if failed > 0 {
+        bail!("{failed} job file(s) failed to download, see above for details");
+    }
+
     Ok(())
 }
 
@@ -118,3 +267,7 @@ fn fmt_bytes(len: u64) -> String {
         .with_units("B")
         .format(len as f64)
 }
+
+fn unix_now() -> Result<i64> {
+    Ok(i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?)
+}
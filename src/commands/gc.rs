@@ -0,0 +1,196 @@
+use anyhow::Context;
+use crate::{
+    args::CommonArgs,
+    cache_db::{CacheDb, CacheEntry, CacheKey},
+    lock_file::LockFile,
+    Result,
+};
+use std::{
+    collections::HashSet,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Reclaim disk space used by old or rarely-used dump files under `--out-dir`.
+///
+/// Only covers job files tracked in the cache database (see `cache_db`); it doesn't touch
+/// `--out-dir`'s `_http_cache`, since job file downloads bypass that cache entirely (see
+/// `http::download_client`) and the small metadata responses it does hold stay bounded on their
+/// own.
+#[derive(clap::Args, Clone, Debug)]
+pub struct Args {
+    #[clap(flatten)]
+    common: CommonArgs,
+
+    /// Remove files whose last use is older than this age, e.g. `30days`, `2weeks`.
+    #[arg(long)]
+    max_age: Option<humantime::Duration>,
+
+    /// Remove least-recently-used files, oldest first, until the total size of tracked files is
+    /// at most this size, e.g. `10GB`.
+    #[arg(long)]
+    max_size: Option<bytesize::ByteSize>,
+
+    /// Only log what would be removed, without deleting anything.
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+}
+
+#[tracing::instrument(level = "trace")]
+pub async fn main(args: Args) -> Result<()> {
+    let _lock = LockFile::acquire(&args.common.out_dir)?;
+
+    let mut db = CacheDb::open(&args.common.out_dir)?;
+    let mut to_remove: Vec<CacheEntry> = Vec::new();
+    let mut already_queued: HashSet<CacheKey> = HashSet::new();
+
+    if let Some(max_age) = args.max_age {
+        let now = unix_now()?;
+        let cutoff = now - i64::try_from(max_age.as_secs()).context("--max-age out of range")?;
+        for entry in db.entries_older_than(cutoff)? {
+            already_queued.insert(entry.key.clone());
+            to_remove.push(entry);
+        }
+    }
+
+    if let Some(max_size) = args.max_size {
+        let entries = db.all_entries_by_last_use()?;
+        to_remove.extend(select_entries_over_max_size(entries, &mut already_queued,
+                                                        max_size.as_u64()));
+    }
+
+    let mut removed = 0u64;
+    let mut removed_len = 0u64;
+
+    for entry in &to_remove {
+        let path = entry.key.path(&args.common.out_dir);
+
+        if args.dry_run {
+            tracing::info!(path = %path.display(), size = entry.size_bytes, "would remove (dry run)");
+            continue;
+        }
+
+        if path.exists() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("while removing '{}'", path.display()))?;
+            remove_empty_ancestors(&path, &args.common.out_dir);
+        }
+        db.remove_entry(&entry.key)?;
+
+        removed += 1;
+        removed_len += entry.size_bytes;
+        tracing::info!(path = %path.display(), size = entry.size_bytes, "removed");
+    }
+
+    tracing::info!(removed,
+                   removed_len,
+                   considered = to_remove.len(),
+                   dry_run = args.dry_run,
+                   "gc command complete");
+
+    Ok(())
+}
+
+/// Given `entries` ordered least-recently-used first, pick enough of those not already in
+/// `already_queued` to bring the total size of the entries that *remain* at or under
+/// `max_size`, inserting each picked entry's key into `already_queued`.
+///
+/// Entries already in `already_queued` (e.g. queued for removal by `--max-age`) are skipped but
+/// still counted out of the starting total, so stacking `--max-age` and `--max-size` in the same
+/// run never removes more than `--max-size` alone would require.
+fn select_entries_over_max_size(
+    entries: Vec<CacheEntry>,
+    already_queued: &mut HashSet<CacheKey>,
+    max_size: u64,
+) -> Vec<CacheEntry> {
+    let mut total: u64 = entries.iter()
+        .filter(|e| !already_queued.contains(&e.key))
+        .map(|e| e.size_bytes)
+        .sum();
+
+    let mut selected = Vec::new();
+    for entry in entries {
+        if already_queued.contains(&entry.key) {
+            continue;
+        }
+        if total <= max_size {
+            break;
+        }
+        total = total.saturating_sub(entry.size_bytes);
+        already_queued.insert(entry.key.clone());
+        selected.push(entry);
+    }
+    selected
+}
+
+fn unix_now() -> Result<i64> {
+    Ok(i64::try_from(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())?)
+}
+
+/// Remove `path`'s parent directories if they're now empty, stopping at (and not removing)
+/// `out_dir` itself.
+fn remove_empty_ancestors(path: &std::path::Path, out_dir: &std::path::Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == out_dir {
+            break;
+        }
+        match std::fs::remove_dir(d) {
+            Ok(()) => dir = d.parent(),
+            Err(_) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(file: &str, size_bytes: u64) -> CacheEntry {
+        CacheEntry {
+            key: CacheKey {
+                dump: "enwiki".to_string(), version: "20230301".to_string(),
+                job: "metacurrentdumprecombine".to_string(), file: file.to_string(),
+            },
+            last_use_unix_secs: 0,
+            size_bytes,
+        }
+    }
+
+    #[test]
+    fn max_size_alone_removes_oldest_until_under_the_cap() {
+        let entries = vec![entry("e1", 30), entry("e2", 25), entry("e3", 15)];
+        let mut already_queued = HashSet::new();
+
+        let removed = select_entries_over_max_size(entries, &mut already_queued, 50);
+
+        // total is 70; removing e1 (oldest) brings it to 40, already <= 50.
+        assert_eq!(removed.iter().map(|e| e.key.file.as_str()).collect::<Vec<_>>(), vec!["e1"]);
+    }
+
+    #[test]
+    fn entries_already_queued_by_max_age_are_not_double_counted() {
+        // e1=30 is already queued for removal (e.g. by --max-age); the true remaining total
+        // after it's gone is 40, already under the 50 cap, so --max-size must not remove
+        // anything else on top of it.
+        let entries = vec![entry("e1", 30), entry("e2", 25), entry("e3", 15)];
+        let mut already_queued = HashSet::new();
+        already_queued.insert(entries[0].key.clone());
+
+        let removed = select_entries_over_max_size(entries, &mut already_queued, 50);
+
+        assert!(removed.is_empty(), "expected no additional removals, got {removed:?}");
+    }
+
+    #[test]
+    fn max_size_keeps_removing_until_the_cap_is_met() {
+        let entries = vec![entry("e1", 30), entry("e2", 25), entry("e3", 15)];
+        let mut already_queued = HashSet::new();
+        already_queued.insert(entries[0].key.clone());
+
+        // With e1 (30) already queued, remaining total starts at 40; capping at 20 still
+        // requires removing e2 as well.
+        let removed = select_entries_over_max_size(entries, &mut already_queued, 20);
+
+        assert_eq!(removed.iter().map(|e| e.key.file.as_str()).collect::<Vec<_>>(), vec!["e2"]);
+    }
+}
@@ -0,0 +1,35 @@
+use crate::{args::CommonArgs, Result};
+use http_cache_reqwest::{Cache, CACacheManager, HttpCache, HttpCacheOptions};
+use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
+
+/// A client for fetching dump metadata (`dumpstatus.json` and friends).
+///
+/// Metadata is always fetched from `https://dumps.wikimedia.org` directly, never from a mirror,
+/// so that we see the freshest list of files and versions. Responses are cached on disk under
+/// `--out-dir`'s `_http_cache` (see `--http-cache-mode`); these are small JSON documents, so this
+/// cache stays bounded on its own and isn't covered by `gc`.
+pub fn metadata_client(common: &CommonArgs) -> Result<ClientWithMiddleware> {
+    let inner = reqwest::Client::builder().build()?;
+
+    Ok(ClientBuilder::new(inner)
+        .with(Cache(HttpCache {
+            mode: common.http_cache_mode.clone(),
+            manager: CACacheManager {
+                path: common.http_cache_path(),
+            },
+            options: HttpCacheOptions::default(),
+        }))
+        .build())
+}
+
+/// A client for downloading job files, used against either `dumps.wikimedia.org` or a mirror.
+///
+/// Deliberately skips the on-disk HTTP cache that `metadata_client` uses: job files are
+/// multi-gigabyte, already verified against a published checksum via `--verify`, and
+/// `download_job_file` already skips re-fetching one that's present under `--out-dir`, so caching
+/// their bodies a second time under `_http_cache` would only waste space that `gc` has no way to
+/// reclaim (`gc` only tracks last-use of files under `--out-dir`'s dump/version/job layout).
+pub fn download_client(_common: &CommonArgs) -> Result<ClientWithMiddleware> {
+    let inner = reqwest::Client::builder().build()?;
+    Ok(ClientBuilder::new(inner).build())
+}
@@ -0,0 +1,32 @@
+use crate::Result;
+use anyhow::Context;
+use fs2::FileExt;
+use std::{fs::File, path::Path};
+
+/// An exclusive advisory lock held over `out_dir` for the lifetime of this value, so that a `gc`
+/// run can't race a concurrent `download` run over the same cache.
+pub struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    pub fn acquire(out_dir: &Path) -> Result<LockFile> {
+        std::fs::create_dir_all(out_dir)?;
+        let path = out_dir.join(".wmd-lock");
+        let file = std::fs::OpenOptions::new().create(true).write(true).open(&path)
+            .with_context(|| format!("while opening lock file '{}'", path.display()))?;
+
+        file.try_lock_exclusive()
+            .with_context(|| format!(
+                "could not lock '{}': is another wmd download or gc command already running \
+                 against this --out-dir?", path.display()))?;
+
+        Ok(LockFile { file })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate lazy_regex;
+
+mod args;
+mod cache_db;
+mod commands;
+mod http;
+mod lock_file;
+mod operations;
+mod result;
+mod temp_dir;
+mod types;
+mod unpack;
+mod user_regex;
+
+pub use crate::{
+    result::Result,
+    temp_dir::TempDir,
+    user_regex::UserRegex,
+};
+
+use clap::Parser;
+
+/// wikimedia-downloader: download and manage local copies of Wikimedia XML dumps.
+#[derive(clap::Parser, Debug)]
+#[command(author, version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Download dump job files.
+    Download(commands::download::Args),
+
+    /// Reclaim disk space used by old or rarely-used dump files.
+    Gc(commands::gc::Args),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let cli = Cli::parse();
+
+    match cli.command {
+        Command::Download(args) => commands::download::main(args).await,
+        Command::Gc(args) => commands::gc::main(args).await,
+    }
+}
@@ -0,0 +1,628 @@
+use crate::{
+    args::{DumpNameArg, JobNameArg},
+    types::{Version, VersionSpec},
+    Result, TempDir, UserRegex,
+};
+use anyhow::{bail, Context};
+use md5::Md5;
+use reqwest_middleware::ClientWithMiddleware;
+use sha1::{Digest, Sha1};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Metadata about a single job file, as parsed from `dumpstatus.json`.
+#[derive(Clone, Debug)]
+pub struct FileMeta {
+    /// The URL path of the file, relative to the dump mirror root.
+    pub url: String,
+
+    /// The file's size in bytes, if known.
+    pub len: Option<u64>,
+
+    /// The expected SHA-1 digest of the file, hex-encoded, if published.
+    pub sha1: Option<String>,
+
+    /// The expected MD5 digest of the file, hex-encoded, if published.
+    pub md5: Option<String>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct DumpStatus {
+    jobs: BTreeMap<String, JobStatus>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct JobStatus {
+    #[serde(default)]
+    files: BTreeMap<String, RawFileStatus>,
+}
+
+#[derive(serde::Deserialize, Debug)]
+struct RawFileStatus {
+    url: String,
+    #[serde(default)]
+    size: Option<u64>,
+    #[serde(default)]
+    sha1: Option<String>,
+    #[serde(default)]
+    md5: Option<String>,
+}
+
+#[tracing::instrument(level = "trace", skip(client))]
+pub async fn get_file_infos(
+    client: &ClientWithMiddleware,
+    dump_name: &DumpNameArg,
+    version_spec: &VersionSpec,
+    job_name: &JobNameArg,
+    file_name_regex: Option<&UserRegex>,
+) -> Result<(Version, BTreeMap<String, FileMeta>)> {
+    let ver = match version_spec {
+        VersionSpec::Version(v) => v.clone(),
+        VersionSpec::Latest => resolve_latest_version(client, dump_name).await?,
+    };
+
+    let url = format!("https://dumps.wikimedia.org/{dump}/{ver}/dumpstatus.json",
+                       dump = &*dump_name.value, ver = ver.0);
+
+    let status: DumpStatus = client.get(&url).send().await?
+        .error_for_status()?
+        .json().await?;
+
+    let job = status.jobs.get(&*job_name.value)
+        .with_context(|| format!(
+            "job '{job}' not found in dumpstatus.json for dump='{dump}' version='{ver}'",
+            job = &*job_name.value, dump = &*dump_name.value, ver = ver.0))?;
+
+    let files = job.files.iter()
+        .filter(|(file_name, _)| match file_name_regex {
+            Some(re) => re.is_match(file_name),
+            None => true,
+        })
+        .map(|(file_name, raw)| (file_name.clone(), FileMeta {
+            url: raw.url.clone(),
+            len: raw.size,
+            sha1: raw.sha1.clone(),
+            md5: raw.md5.clone(),
+        }))
+        .collect();
+
+    Ok((ver, files))
+}
+
+async fn resolve_latest_version(
+    client: &ClientWithMiddleware,
+    dump_name: &DumpNameArg,
+) -> Result<Version> {
+    #[derive(serde::Deserialize)]
+    struct Latest {
+        version: String,
+    }
+
+    let url = format!("https://dumps.wikimedia.org/{dump}/latest/dumpstatus.json",
+                       dump = &*dump_name.value);
+    let latest: Latest = client.get(&url).send().await?.error_for_status()?.json().await?;
+    Ok(Version(latest.version))
+}
+
+/// Which published digest, if any, to verify downloaded files against.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum VerifyMode {
+    Sha1,
+    Md5,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DownloadJobFileResultKind {
+    /// The file was downloaded fresh in this run.
+    DownloadOk,
+
+    /// The file was already present in `out_dir` and passed verification (if requested).
+    ExistingOk,
+
+    /// The file was already present in `out_dir` but failed verification against its
+    /// published digest, so it is likely corrupt and should be re-downloaded.
+    ChecksumMismatch,
+}
+
+#[derive(Debug)]
+pub struct DownloadJobFileResult {
+    pub kind: DownloadJobFileResultKind,
+    pub len: u64,
+
+    /// Where the file was (or already is) stored under `out_dir`.
+    pub final_path: PathBuf,
+
+    /// Bytes actually pulled over the network this run, as opposed to bytes that were already
+    /// on disk in a `.part` file and resumed. Zero for `ExistingOk` and `ChecksumMismatch`.
+    pub transferred_len: u64,
+
+    /// The mirror that ultimately served this file, if a download was attempted.
+    pub mirror: Option<String>,
+
+    /// Mirrors that were tried and failed (connection error, 404/403, or checksum mismatch)
+    /// before `mirror` succeeded. When every mirror fails, `download_job_file` returns an error
+    /// instead of a result, so those failures are not reported here.
+    pub failed_mirrors: Vec<String>,
+}
+
+#[tracing::instrument(level = "trace", skip(client, temp_dir))]
+pub async fn download_job_file(
+    client: &ClientWithMiddleware,
+    dump_name: &DumpNameArg,
+    ver: &Version,
+    job_name: &JobNameArg,
+    mirrors: &[String],
+    file_meta: &FileMeta,
+    out_dir: &Path,
+    temp_dir: &TempDir,
+    resume: bool,
+    verify: VerifyMode,
+) -> Result<DownloadJobFileResult> {
+    let file_name = file_name_from_url(&file_meta.url)?;
+    let final_path = out_dir.join(&*dump_name.value).join(&ver.0).join(&*job_name.value)
+        .join(file_name);
+
+    if final_path.exists() {
+        let len = std::fs::metadata(&final_path)?.len();
+
+        // `verify_file` reads and hashes the whole file synchronously; run it on a blocking
+        // thread so verifying one large `ExistingOk` file doesn't stall the async runtime
+        // thread polling other downloads in the concurrent pipeline.
+        let verify_ok = verify == VerifyMode::None || {
+            let final_path = final_path.clone();
+            let file_meta = file_meta.clone();
+            tokio::task::spawn_blocking(move || verify_file(&final_path, &file_meta, verify))
+                .await.context("verify_file task panicked")??
+        };
+
+        if verify_ok {
+            return Ok(DownloadJobFileResult {
+                kind: DownloadJobFileResultKind::ExistingOk, len, transferred_len: 0,
+                final_path, mirror: None, failed_mirrors: vec![],
+            });
+        }
+
+        tracing::warn!(path = %final_path.display(), "existing file failed checksum verification");
+
+        // Remove the corrupt file rather than leaving it in place: otherwise it would never be
+        // re-downloaded (the `final_path.exists()` check above would keep finding it and
+        // re-verifying it forever).
+        std::fs::remove_file(&final_path)
+            .with_context(|| format!("while removing corrupt file '{}'", final_path.display()))?;
+
+        return Ok(DownloadJobFileResult {
+            kind: DownloadJobFileResultKind::ChecksumMismatch, len, transferred_len: 0,
+            final_path, mirror: None, failed_mirrors: vec![],
+        });
+    }
+
+    std::fs::create_dir_all(final_path.parent().expect("final_path has a parent"))?;
+
+    if mirrors.is_empty() {
+        bail!("no mirror URLs configured");
+    }
+
+    let part_path = temp_dir.path().join(format!("{file_name}.part"));
+
+    let attempt = try_mirrors_in_order(mirrors, |mirror_url| async move {
+        try_download_from_mirror(client, mirror_url, file_meta, &part_path, resume, verify).await
+            .map_err(|e| {
+                tracing::warn!(mirror = %mirror_url, file = file_name, error = %e,
+                                "mirror failed, trying next mirror");
+                e
+            })
+    }).await;
+
+    match attempt {
+        Ok((downloaded, mirror_url, failed_mirrors)) => {
+            std::fs::rename(&part_path, &final_path)?;
+            tracing::info!(mirror = %mirror_url, file = file_name, "downloaded from mirror");
+            Ok(DownloadJobFileResult {
+                kind: DownloadJobFileResultKind::DownloadOk,
+                len: downloaded.len,
+                transferred_len: downloaded.transferred_len,
+                final_path, mirror: Some(mirror_url), failed_mirrors,
+            })
+        },
+        Err(e) => {
+            std::fs::remove_file(&part_path).ok();
+            Err(e).with_context(|| format!("all {} mirror(s) failed for file '{file_name}'", mirrors.len()))
+        },
+    }
+}
+
+/// Try each of `mirrors` in order via `attempt`, stopping at the first success.
+///
+/// Returns the successful value together with the mirror that produced it and the mirrors that
+/// were tried and failed before it. Once every mirror has failed, returns the last attempt's
+/// error (the earlier failures are only logged by `attempt` itself, matching how
+/// `download_job_file` reports them).
+///
+/// Factored out of `download_job_file`'s mirror loop so the ordering and bookkeeping can be
+/// exercised with fake results in a unit test, without needing real mirrors to fail against.
+async fn try_mirrors_in_order<T, F, Fut>(
+    mirrors: &[String],
+    mut attempt: F,
+) -> Result<(T, String, Vec<String>)>
+where
+    F: FnMut(&str) -> Fut,
+    Fut: std::future::Future<Output = Result<T>>,
+{
+    let mut failed_mirrors = Vec::new();
+    let mut last_err = None;
+
+    for mirror_url in mirrors {
+        match attempt(mirror_url).await {
+            Ok(value) => return Ok((value, mirror_url.clone(), failed_mirrors)),
+            Err(e) => {
+                failed_mirrors.push(mirror_url.clone());
+                last_err = Some(e);
+            },
+        }
+    }
+
+    Err(last_err.expect("at least one mirror was tried"))
+}
+
+struct MirrorDownload {
+    /// Total length of the file on disk after this attempt.
+    len: u64,
+
+    /// Bytes pulled over the network during this attempt, excluding any bytes that were
+    /// already present in `part_path` from a previous, interrupted attempt.
+    transferred_len: u64,
+}
+
+/// Attempt to download `file_meta` from a single mirror into `part_path`, resuming from a
+/// previous partial attempt if one is present and `resume` is set, and verifying its digest
+/// if requested.
+async fn try_download_from_mirror(
+    client: &ClientWithMiddleware,
+    mirror_url: &str,
+    file_meta: &FileMeta,
+    part_path: &Path,
+    resume: bool,
+    verify: VerifyMode,
+) -> Result<MirrorDownload> {
+    let url = format!("{mirror_url}/{file_url}",
+                       mirror_url = mirror_url.trim_end_matches('/'),
+                       file_url = file_meta.url.trim_start_matches('/'));
+
+    if !resume {
+        std::fs::remove_file(part_path).ok();
+    }
+    let existing_len = std::fs::metadata(part_path).map(|m| m.len()).unwrap_or(0);
+
+    // The `.part` file can already hold the complete download if a previous run was killed
+    // after the last byte arrived but before `download_job_file` renamed it into place; in that
+    // case there's nothing left to fetch, so verify what's on disk instead of sending a Range
+    // request a compliant server may have nothing left to answer.
+    if existing_len > 0 && file_meta.len == Some(existing_len) {
+        if verify_part_file(part_path, file_meta, verify).await? {
+            return Ok(MirrorDownload { len: existing_len, transferred_len: 0 });
+        }
+        tracing::warn!(path = %part_path.display(),
+                        "existing .part file matched expected length but failed checksum, \
+                         restarting download from scratch");
+        std::fs::remove_file(part_path).ok();
+    }
+    let existing_len = std::fs::metadata(part_path).map(|m| m.len()).unwrap_or(0);
+
+    let mut req = client.get(&url);
+    if existing_len > 0 {
+        req = req.header(reqwest::header::RANGE, format!("bytes={existing_len}-"));
+    }
+    let resp = req.send().await?;
+
+    if existing_len > 0 && resp.status() == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+        // The server has nothing left to send past `existing_len`, most likely because the
+        // `.part` file is already complete (see above) but `file_meta.len` wasn't published so
+        // the check above couldn't short-circuit the request. Trust what's on disk rather than
+        // treating this as a mirror failure.
+        tracing::warn!(%url, existing_len,
+                        "mirror replied 416 Range Not Satisfiable, treating existing .part file as complete");
+        if verify_part_file(part_path, file_meta, verify).await? {
+            return Ok(MirrorDownload { len: existing_len, transferred_len: 0 });
+        }
+        bail!("mirror has no more bytes to send for '{url}' but the existing .part file fails \
+               checksum verification");
+    }
+
+    let resp = resp.error_for_status()?;
+
+    // From here on this attempt writes to `part_path`; clean it up on any failure (not just a
+    // checksum mismatch) so a later mirror attempt in the same `download_job_file` call never
+    // mistakes a half-written, possibly-truncated file left by *this* attempt for a genuine
+    // cross-run resume and appends its own bytes onto the end of it.
+    match receive_and_verify(&url, resp, part_path, existing_len, file_meta, verify).await {
+        Ok(downloaded) => Ok(downloaded),
+        Err(e) => {
+            std::fs::remove_file(part_path).ok();
+            Err(e)
+        },
+    }
+}
+
+/// Stream `resp`'s body into `part_path` (appending after `existing_len` bytes already hashed
+/// into the state implied by `resuming`, or starting fresh), then check the result against
+/// `file_meta`'s expected length and digest.
+async fn receive_and_verify(
+    url: &str,
+    resp: reqwest::Response,
+    part_path: &Path,
+    existing_len: u64,
+    file_meta: &FileMeta,
+    verify: VerifyMode,
+) -> Result<MirrorDownload> {
+    let resuming = existing_len > 0 && resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    if existing_len > 0 && !resuming {
+        tracing::warn!(%url, "mirror did not honor range request, restarting download from scratch");
+    }
+
+    let mut sha1 = Sha1::new();
+    let mut md5 = Md5::new();
+
+    let (mut file, mut len) = if resuming {
+        // Stream the existing `.part` bytes through the hashers via `tokio::fs` instead of a
+        // blocking read, so resuming near the end of a multi-gigabyte dump file neither loads
+        // gigabytes into memory nor blocks the async runtime thread on disk IO while other
+        // downloads are in flight.
+        let (s, m) = hash_existing_part(part_path).await?;
+        sha1 = s;
+        md5 = m;
+        (tokio::fs::OpenOptions::new().append(true).open(part_path).await?, existing_len)
+    } else {
+        (tokio::fs::File::create(part_path).await?, 0)
+    };
+    let start_len = len;
+
+    let mut resp = resp;
+    while let Some(chunk) = resp.chunk().await? {
+        file.write_all(&chunk).await?;
+        sha1.update(&chunk);
+        md5.update(&chunk);
+        len += chunk.len() as u64;
+    }
+    file.flush().await?;
+    drop(file);
+
+    if let Some(expected_len) = file_meta.len {
+        if len != expected_len {
+            bail!("downloaded length {len} does not match expected length {expected_len} for '{url}'");
+        }
+    }
+
+    let digest_ok = match verify {
+        VerifyMode::Sha1 => digests_match(file_meta.sha1.as_deref(), &hex::encode(sha1.finalize()), url),
+        VerifyMode::Md5 => digests_match(file_meta.md5.as_deref(), &hex::encode(md5.finalize()), url),
+        VerifyMode::None => true,
+    };
+
+    if !digest_ok {
+        bail!("checksum mismatch downloading '{url}'");
+    }
+
+    Ok(MirrorDownload { len, transferred_len: len - start_len })
+}
+
+/// Stream `path`'s existing bytes through fresh SHA-1/MD5 hashers via `tokio::fs`, rather than a
+/// blocking read, so hashing a partially- or fully-downloaded multi-gigabyte `.part` file neither
+/// loads it all into memory nor blocks the async runtime thread while other downloads are in
+/// flight.
+async fn hash_existing_part(path: &Path) -> Result<(Sha1, Md5)> {
+    let mut sha1 = Sha1::new();
+    let mut md5 = Md5::new();
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        sha1.update(&buf[..n]);
+        md5.update(&buf[..n]);
+    }
+    Ok((sha1, md5))
+}
+
+/// Verify a `.part` file believed to already hold the complete download against `file_meta`'s
+/// published digest, e.g. after a `416 Range Not Satisfiable` response.
+async fn verify_part_file(path: &Path, file_meta: &FileMeta, verify: VerifyMode) -> Result<bool> {
+    if verify == VerifyMode::None {
+        return Ok(true);
+    }
+
+    let (sha1, md5) = hash_existing_part(path).await?;
+    let path_str = path.to_string_lossy();
+    Ok(match verify {
+        VerifyMode::Sha1 => digests_match(file_meta.sha1.as_deref(), &hex::encode(sha1.finalize()), &path_str),
+        VerifyMode::Md5 => digests_match(file_meta.md5.as_deref(), &hex::encode(md5.finalize()), &path_str),
+        VerifyMode::None => true,
+    })
+}
+
+/// Compare a published digest against one we computed for `subject` (a URL or path, used only
+/// for logging). Returns `true` if there's nothing to compare against, since a dump not
+/// publishing a digest for a file isn't itself an error, but this is logged: silently treating
+/// "no digest to check" the same as "digest checked and matched" would defeat the point of
+/// `--verify` for exactly the files most likely to need it.
+fn digests_match(expected: Option<&str>, actual: &str, subject: &str) -> bool {
+    match expected {
+        Some(expected) => expected.eq_ignore_ascii_case(actual),
+        None => {
+            tracing::warn!(subject, "no published digest to verify against, skipping verification");
+            true
+        },
+    }
+}
+
+/// Verify `path` against `file_meta`'s published digest, streaming it through the hasher in
+/// fixed-size chunks rather than reading the whole (potentially multi-gigabyte) file into memory.
+fn verify_file(path: &Path, file_meta: &FileMeta, verify: VerifyMode) -> Result<bool> {
+    if verify == VerifyMode::None {
+        return Ok(true);
+    }
+
+    let mut file = std::io::BufReader::new(std::fs::File::open(path)?);
+    let mut sha1 = Sha1::new();
+    let mut md5 = Md5::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = std::io::Read::read(&mut file, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        sha1.update(&buf[..n]);
+        md5.update(&buf[..n]);
+    }
+
+    let path_str = path.to_string_lossy();
+    Ok(match verify {
+        VerifyMode::Sha1 => digests_match(file_meta.sha1.as_deref(), &hex::encode(sha1.finalize()), &path_str),
+        VerifyMode::Md5 => digests_match(file_meta.md5.as_deref(), &hex::encode(md5.finalize()), &path_str),
+        VerifyMode::None => true,
+    })
+}
+
+fn file_name_from_url(url: &str) -> Result<&str> {
+    url.rsplit('/').next().filter(|s| !s.is_empty())
+        .with_context(|| format!("could not extract file name from url '{url}'"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_match_is_case_insensitive() {
+        assert!(digests_match(Some("ABCDEF"), "abcdef", "subject"));
+        assert!(digests_match(Some("abcdef"), "abcdef", "subject"));
+    }
+
+    #[test]
+    fn digests_match_rejects_a_mismatch() {
+        assert!(!digests_match(Some("abcdef"), "123456", "subject"));
+    }
+
+    #[test]
+    fn digests_match_passes_and_warns_when_no_digest_is_published() {
+        assert!(digests_match(None, "abcdef", "subject"));
+    }
+
+    fn write_temp_file(label: &str, contents: &[u8]) -> PathBuf {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+            .unwrap().as_nanos();
+        let path = std::env::temp_dir()
+            .join(format!("wmd-operations-test-{label}-{}-{nanos}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn file_meta_for(contents: &[u8]) -> FileMeta {
+        FileMeta {
+            url: "foo.xml.bz2".to_string(),
+            len: Some(contents.len() as u64),
+            sha1: Some(hex::encode(Sha1::digest(contents))),
+            md5: Some(hex::encode(Md5::digest(contents))),
+        }
+    }
+
+    #[test]
+    fn verify_file_passes_when_the_digest_matches() {
+        let contents = b"some job file contents";
+        let path = write_temp_file("verify-ok", contents);
+
+        let ok = verify_file(&path, &file_meta_for(contents), VerifyMode::Sha1).unwrap();
+
+        std::fs::remove_file(&path).ok();
+        assert!(ok);
+    }
+
+    #[test]
+    fn verify_file_fails_on_a_mismatched_digest() {
+        let contents = b"some job file contents";
+        let path = write_temp_file("verify-mismatch", contents);
+        let mut file_meta = file_meta_for(contents);
+        file_meta.sha1 = Some("0000000000000000000000000000000000000000".to_string());
+
+        let ok = verify_file(&path, &file_meta, VerifyMode::Sha1).unwrap();
+
+        std::fs::remove_file(&path).ok();
+        assert!(!ok);
+    }
+
+    #[test]
+    fn verify_file_passes_when_no_digest_is_published() {
+        let contents = b"some job file contents";
+        let path = write_temp_file("verify-no-digest", contents);
+        let mut file_meta = file_meta_for(contents);
+        file_meta.sha1 = None;
+
+        let ok = verify_file(&path, &file_meta, VerifyMode::Sha1).unwrap();
+
+        std::fs::remove_file(&path).ok();
+        assert!(ok);
+    }
+
+    #[test]
+    fn file_name_from_url_extracts_the_last_path_segment() {
+        assert_eq!(file_name_from_url("enwiki-20230301-pages-articles.xml.bz2").unwrap(),
+                   "enwiki-20230301-pages-articles.xml.bz2");
+        assert_eq!(file_name_from_url("enwiki/20230301/pages-articles.xml.bz2").unwrap(),
+                   "pages-articles.xml.bz2");
+    }
+
+    #[test]
+    fn file_name_from_url_rejects_a_trailing_slash() {
+        assert!(file_name_from_url("enwiki/20230301/").is_err());
+    }
+
+    #[test]
+    fn file_name_from_url_rejects_an_empty_url() {
+        assert!(file_name_from_url("").is_err());
+    }
+
+    fn mirrors(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn try_mirrors_in_order_returns_the_first_success() {
+        let mirrors = mirrors(&["a", "b", "c"]);
+
+        let (value, mirror, failed) = try_mirrors_in_order(&mirrors, |m| async move {
+            if m == "b" { Ok(42) } else { bail!("no good") }
+        }).await.unwrap();
+
+        assert_eq!(value, 42);
+        assert_eq!(mirror, "b");
+        assert_eq!(failed, vec!["a".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn try_mirrors_in_order_does_not_try_mirrors_after_a_success() {
+        let mirrors = mirrors(&["a", "b", "c"]);
+        let tried = std::sync::Mutex::new(Vec::new());
+
+        try_mirrors_in_order(&mirrors, |m| {
+            tried.lock().unwrap().push(m.to_string());
+            async move { Ok::<_, anyhow::Error>(()) }
+        }).await.unwrap();
+
+        assert_eq!(*tried.lock().unwrap(), vec!["a".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn try_mirrors_in_order_fails_once_every_mirror_has_failed() {
+        let mirrors = mirrors(&["a", "b"]);
+
+        let result: Result<((), String, Vec<String>)> =
+            try_mirrors_in_order(&mirrors, |_| async move { bail!("mirror unreachable") }).await;
+
+        assert!(result.is_err());
+    }
+}
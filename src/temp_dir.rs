@@ -0,0 +1,37 @@
+use crate::Result;
+use std::path::{Path, PathBuf};
+
+/// A temporary directory used to stage downloads before they're moved into place.
+///
+/// By default the directory is removed when this value is dropped; pass `keep = true` to
+/// `TempDir::create` to leave it behind for inspection (e.g. `download --keep-temp-dir`).
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    keep: bool,
+}
+
+impl TempDir {
+    pub fn create(out_dir: &Path, keep: bool) -> Result<TempDir> {
+        let path = out_dir.join("_tmp");
+        std::fs::create_dir_all(&path)?;
+        Ok(TempDir { path, keep })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if self.keep {
+            return;
+        }
+
+        if let Err(e) = std::fs::remove_dir_all(&self.path) {
+            tracing::warn!(path = %self.path.display(), error = %e,
+                            "failed to remove temporary directory");
+        }
+    }
+}
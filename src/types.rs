@@ -0,0 +1,27 @@
+use std::fmt;
+
+/// An 8-digit Wikimedia dump version, e.g. `20230301`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Version(pub String);
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A dump version as given on the command line: either a specific `Version`, or `latest`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum VersionSpec {
+    Latest,
+    Version(Version),
+}
+
+impl fmt::Display for VersionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VersionSpec::Latest => write!(f, "latest"),
+            VersionSpec::Version(v) => write!(f, "{v}"),
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use crate::Result;
+use std::path::{Path, PathBuf};
+
+/// The path a compressed job file would decompress to, or `None` if its extension isn't one we
+/// know how to decompress (e.g. `.7z`).
+fn unpacked_path(compressed_path: &Path) -> Option<PathBuf> {
+    let name = compressed_path.file_name()?.to_str()?;
+
+    for ext in [".bz2", ".gz"] {
+        if let Some(stripped) = name.strip_suffix(ext) {
+            return Some(compressed_path.with_file_name(stripped));
+        }
+    }
+
+    None
+}
+
+/// Decompress `compressed_path` into a sibling file with its compression extension removed,
+/// e.g. `foo.xml.bz2` -> `foo.xml`.
+///
+/// Returns the number of decompressed bytes written, or `None` if nothing was done because the
+/// extension isn't supported, or an up-to-date decompressed output already exists.
+pub fn unpack_job_file(compressed_path: &Path, remove_compressed: bool) -> Result<Option<u64>> {
+    let Some(out_path) = unpacked_path(compressed_path) else {
+        tracing::debug!(path = %compressed_path.display(),
+                         "no known decompressor for this file's extension, skipping --unpack");
+        return Ok(None);
+    };
+
+    if out_path.exists()
+        && std::fs::metadata(&out_path)?.modified()? >= std::fs::metadata(compressed_path)?.modified()? {
+        tracing::debug!(path = %out_path.display(), "decompressed output is already up to date, skipping");
+        return Ok(None);
+    }
+
+    let compressed_name = compressed_path.file_name()
+        .expect("compressed_path has a file name")
+        .to_str()
+        .expect("compressed_path is valid UTF-8");
+
+    let compressed_file = std::fs::File::open(compressed_path)?;
+    let mut reader: Box<dyn std::io::Read> = if compressed_name.ends_with(".bz2") {
+        Box::new(bzip2::read::BzDecoder::new(compressed_file))
+    } else if compressed_name.ends_with(".gz") {
+        Box::new(flate2::read::GzDecoder::new(compressed_file))
+    } else {
+        unreachable!("unpacked_path() only returns Some() for extensions handled above")
+    };
+
+    // Decompress into a `.part` file first and only rename it into place once `io::copy`
+    // succeeds, so a failure partway through (e.g. a truncated `.bz2` from a bad mirror) can't
+    // leave a partial `out_path` behind with a fresh mtime that would look "up to date" above
+    // on the next run.
+    let part_path = out_path.with_file_name(format!(
+        "{}.part",
+        out_path.file_name().expect("out_path has a file name").to_str()
+            .expect("out_path is valid UTF-8")));
+    let mut out_file = std::fs::File::create(&part_path)?;
+    let len = std::io::copy(&mut reader, &mut out_file)?;
+    drop(out_file);
+    std::fs::rename(&part_path, &out_path)?;
+
+    if remove_compressed {
+        std::fs::remove_file(compressed_path)?;
+    }
+
+    tracing::info!(path = %out_path.display(), len, "unpacked job file");
+
+    Ok(Some(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// A fresh, uniquely-named directory under the system temp dir, removed when dropped.
+    struct TestOutDir(PathBuf);
+
+    impl TestOutDir {
+        fn new(label: &str) -> TestOutDir {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+            let path = std::env::temp_dir()
+                .join(format!("wmd-unpack-test-{label}-{}-{nanos}", std::process::id()));
+            std::fs::create_dir_all(&path).unwrap();
+            TestOutDir(path)
+        }
+    }
+
+    impl Drop for TestOutDir {
+        fn drop(&mut self) {
+            std::fs::remove_dir_all(&self.0).ok();
+        }
+    }
+
+    #[test]
+    fn unpacked_path_strips_bz2() {
+        assert_eq!(unpacked_path(Path::new("/out/enwiki-pages.xml.bz2")),
+                   Some(PathBuf::from("/out/enwiki-pages.xml")));
+    }
+
+    #[test]
+    fn unpacked_path_strips_gz() {
+        assert_eq!(unpacked_path(Path::new("/out/enwiki-pages.xml.gz")),
+                   Some(PathBuf::from("/out/enwiki-pages.xml")));
+    }
+
+    #[test]
+    fn unpacked_path_is_none_for_an_unsupported_extension() {
+        assert_eq!(unpacked_path(Path::new("/out/enwiki-pages.xml.7z")), None);
+    }
+
+    #[test]
+    fn unpack_job_file_decompresses_a_gz_file() {
+        let dir = TestOutDir::new("decompress-gz");
+        let compressed_path = dir.0.join("foo.xml.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"hello job file").unwrap();
+        std::fs::write(&compressed_path, encoder.finish().unwrap()).unwrap();
+
+        let len = unpack_job_file(&compressed_path, false).unwrap();
+
+        assert_eq!(len, Some(14));
+        assert_eq!(std::fs::read(dir.0.join("foo.xml")).unwrap(), b"hello job file");
+        assert!(compressed_path.exists(), "compressed original should remain without --remove-compressed");
+    }
+
+    #[test]
+    fn unpack_job_file_removes_the_compressed_original_when_asked() {
+        let dir = TestOutDir::new("remove-compressed");
+        let compressed_path = dir.0.join("foo.xml.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"hello job file").unwrap();
+        std::fs::write(&compressed_path, encoder.finish().unwrap()).unwrap();
+
+        unpack_job_file(&compressed_path, true).unwrap();
+
+        assert!(!compressed_path.exists());
+    }
+
+    #[test]
+    fn unpack_job_file_skips_an_unsupported_extension() {
+        let dir = TestOutDir::new("unsupported-ext");
+        let compressed_path = dir.0.join("foo.xml.7z");
+        std::fs::write(&compressed_path, b"not really 7z").unwrap();
+
+        let len = unpack_job_file(&compressed_path, false).unwrap();
+
+        assert_eq!(len, None);
+    }
+
+    #[test]
+    fn unpack_job_file_skips_an_up_to_date_decompressed_output() {
+        let dir = TestOutDir::new("up-to-date");
+        let compressed_path = dir.0.join("foo.xml.gz");
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, b"hello job file").unwrap();
+        std::fs::write(&compressed_path, encoder.finish().unwrap()).unwrap();
+
+        // A decompressed output written after (so with a newer mtime than) the compressed
+        // input should be left alone, not re-decompressed from possibly-stale bytes.
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        let out_path = dir.0.join("foo.xml");
+        std::fs::write(&out_path, b"already decompressed").unwrap();
+
+        let len = unpack_job_file(&compressed_path, false).unwrap();
+
+        assert_eq!(len, None);
+        assert_eq!(std::fs::read(&out_path).unwrap(), b"already decompressed");
+    }
+}
@@ -0,0 +1,31 @@
+use std::{
+    fmt,
+    ops::Deref,
+    str::FromStr,
+};
+
+/// A `regex::Regex` that can be parsed from a `clap` argument.
+#[derive(Clone, Debug)]
+pub struct UserRegex(regex::Regex);
+
+impl Deref for UserRegex {
+    type Target = regex::Regex;
+
+    fn deref(&self) -> &regex::Regex {
+        &self.0
+    }
+}
+
+impl FromStr for UserRegex {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<UserRegex, regex::Error> {
+        Ok(UserRegex(regex::Regex::new(s)?))
+    }
+}
+
+impl fmt::Display for UserRegex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.as_str())
+    }
+}